@@ -0,0 +1,37 @@
+//! Salted hash commitments of Applicant data
+//!
+//! The Applicant computes a commitment per property off-chain and keeps the salt, only the
+//! commitment is uploaded. Revealing the salt and value later proves what was reviewed.
+
+use pbc_traits::ReadWriteRPC;
+use sha2::{Digest, Sha256};
+
+use crate::SubjectInfo;
+
+// SHA-256(salt || len(property_name) || property_name || property_value), the length prefix
+// keeps name/value boundaries unambiguous
+pub fn commit_subject_info(info: &SubjectInfo, salt: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update((info.property_name.len() as u32).to_be_bytes());
+    hasher.update(info.property_name.as_bytes());
+    hasher.update(info.property_value.as_bytes());
+    hasher.finalize().into()
+}
+
+pub fn verify_subject_commitment(info: &SubjectInfo, salt: &[u8; 32], commitment: &[u8; 32]) -> bool {
+    commit_subject_info(info, salt) == *commitment
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// SHA-256 over the RPC serialization, kept in place of data that has been erased.
+// The hash is unsalted, for low-entropy data such as names and dates of birth it can be
+// brute-forced, it only proves what was erased to someone who already holds the data
+pub fn content_hash<T: ReadWriteRPC>(value: &T) -> [u8; 32] {
+    let mut bytes: Vec<u8> = vec![];
+    value.rpc_write_to(&mut bytes).unwrap();
+    Sha256::digest(&bytes).into()
+}
@@ -1,12 +1,15 @@
 //! pbc-kyc
 
+pub mod commitment;
+pub mod merkle;
+
 #[macro_use]
 extern crate pbc_contract_codegen;
 
 use pbc_contract_common::address::{Address,AddressType};
 use pbc_contract_common::context::{ContractContext, CallbackContext};
-use pbc_contract_common::events::EventGroup;
-use pbc_contract_common::shortname::Shortname;
+use pbc_contract_common::events::{CallbackBuilder, EventGroup, EventGroupBuilder};
+use pbc_contract_common::shortname::{Shortname, ShortnameCallback};
 use pbc_contract_common::sorted_vec_map::SortedVecMap;
 use read_write_state_derive::ReadWriteState;
 use read_write_rpc_derive::ReadWriteRPC;
@@ -16,9 +19,484 @@ use create_type_spec_derive::CreateTypeSpec;
 #[state]
 pub struct ContractState {
     owner: Address,
+    pending_owner: Option<Address>, // Proposed new owner, set until accepted or cancelled
     registry_address: Address,
     storage_adddress: Address,
-    kycs: SortedVecMap<u128, Kyc>, // Key: Applicant DID, Value: KYC
+    kycs: SortedVecMap<u128, Kyc>, // Key: KYC ID, Value: KYC
+    next_kyc_id: u128, // Monotonically increasing, IDs are never reused
+    next_vc_id: u128, // Monotonically increasing, every VC uploaded to the VC Storage Contract gets a fresh ID
+    kycs_by_did: SortedVecMap<String, Vec<u128>>, // Key: Applicant DID, Value: KYC IDs in submission order
+    roles: SortedVecMap<Address, Vec<Role>>, // Key: Account, Value: Roles granted to the Account
+    approval_threshold: u32, // Number of matching Reviewer votes required to decide a KYC
+    privacy_mode: bool, // When set, plaintext Applicant data is rejected, only commitments or ciphertexts are accepted
+    retention_period: i64, // Milliseconds after which stale KYCs may be pruned, 0 disables pruning
+    encryption_keys: SortedVecMap<u32, EncryptionKey>, // Key: Key ID, Value: Compliance public key
+    active_key_id: Option<u32>, // Key new encrypted submissions have to use
+    next_key_id: u32,
+    failed_submissions: Vec<FailedSubmission>, // Most recent failed callbacks, oldest first
+    pending_requests: SortedVecMap<u64, PendingRequest>, // Key: Request ID, Value: DID Registry check in flight
+    next_request_id: u64,
+}
+
+// A DID Registry check that has been sent but whose callback has not arrived yet
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct PendingRequest {
+    kind: RequestKind,
+    kyc_id: Option<u128>, // Not known for uploads
+    applicant_did: String,
+    sender: Address,
+    registry_address: Address, // DID Registry the check was sent to
+}
+
+impl PendingRequest {
+    fn failure(self, reason: &str, failed_at: i64) -> FailedSubmission {
+        FailedSubmission {
+            kind: self.kind,
+            kyc_id: self.kyc_id,
+            applicant_did: self.applicant_did,
+            sender: self.sender,
+            reason: reason.to_string(),
+            failed_at,
+        }
+    }
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct FailedSubmission {
+    kind: RequestKind,
+    kyc_id: Option<u128>, // Not known for failed uploads
+    applicant_did: String,
+    sender: Address,
+    reason: String,
+    failed_at: i64,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RequestKind {
+    #[discriminant(0)]
+    Upload {},
+    #[discriminant(1)]
+    Resubmission {},
+    #[discriminant(2)]
+    Withdrawal {},
+    #[discriminant(3)]
+    Erasure {},
+    #[discriminant(4)]
+    VcUpload {},
+    #[discriminant(5)]
+    VcRevocation {},
+}
+
+// Keeps the failure log, and with it the state size, bounded
+const MAX_FAILED_SUBMISSIONS: usize = 100;
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct EncryptionKey {
+    public_key: Vec<u8>,
+    registered_at: i64,
+    retired_at: Option<i64>, // Set once a newer key has been registered
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    // Manages roles and contract configuration
+    #[discriminant(0)]
+    Admin {},
+    // Decides on submitted KYCs
+    #[discriminant(1)]
+    Reviewer {},
+    // Issues VCs for approved KYCs
+    #[discriminant(2)]
+    Issuer {},
+}
+
+impl ContractState {
+    // The owner always holds Admin so the contract can never be locked out
+    fn has_role(&self, account: &Address, role: Role) -> bool {
+        if *account == self.owner && role == (Role::Admin {}) {
+            return true;
+        }
+        self.roles.get(account).is_some_and(|roles| roles.contains(&role))
+    }
+
+    fn has_active_kyc(&self, applicant_did: &str) -> bool {
+        match self.kycs_by_did.get(applicant_did) {
+            Some(kyc_ids) => kyc_ids
+                .iter()
+                .any(|kyc_id| self.kycs.get(kyc_id).is_some_and(|kyc| kyc.status.is_active())),
+            None => false,
+        }
+    }
+
+    // Checked again in the callbacks, the privacy mode or the active key may change while a request is in flight
+    fn rejection_reason(&self, applicant_info: &ApplicantData) -> Option<&'static str> {
+        match applicant_info {
+            ApplicantData::Plain { .. } if self.privacy_mode => {
+                Some("Plaintext Applicant Info Not Accepted in Privacy Mode!")
+            }
+            ApplicantData::Encrypted { key_id, .. } if self.active_key_id != Some(*key_id) => {
+                Some("Encryption Key Not Active!")
+            }
+            ApplicantData::Erased { .. } => Some("Erased Applicant Info Cannot Be Submitted!"),
+            _ => None,
+        }
+    }
+
+    fn assert_accepts(&self, applicant_info: &ApplicantData) {
+        if let Some(reason) = self.rejection_reason(applicant_info) {
+            panic!("{}", reason);
+        }
+    }
+
+    fn has_pending_request(&self, applicant_did: &str) -> bool {
+        self.pending_requests.values().any(|request| request.applicant_did == *applicant_did)
+    }
+
+    fn track_request(&mut self, request: PendingRequest) -> u64 {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.pending_requests.insert(request_id, request);
+        request_id
+    }
+
+    // Every callback consumes its request, so a replayed or duplicate callback is rejected
+    fn take_request(&mut self, request_id: u64) -> PendingRequest {
+        self.pending_requests.remove(&request_id).expect("Unknown or Duplicate Request!")
+    }
+
+    // The answer came from a DID Registry that is no longer configured
+    fn stale_reason(&self, request: &PendingRequest) -> Option<&'static str> {
+        if request.registry_address != self.registry_address {
+            return Some("DID Registry Changed While Request In Flight!");
+        }
+        None
+    }
+
+    // Tracks the request and asks the DID Registry whether the Sender may act for the Applicant DID.
+    // The returned callback already carries the request ID, further arguments are added by the caller
+    fn check_registry<'a>(
+        &mut self,
+        event_group_builder: &'a mut EventGroupBuilder,
+        kind: RequestKind,
+        kyc_id: Option<u128>,
+        applicant_did: String,
+        sender: Address,
+        callback: ShortnameCallback,
+    ) -> CallbackBuilder<'a> {
+        assert!(self.registry_address.identifier != [0x00; 20], "Please configure a valid DID Registry Address!");
+        assert!(!self.has_pending_request(&applicant_did), "Request Already In Flight for DID!");
+
+        let registry_address = self.registry_address;
+        let request_id = self.track_request(PendingRequest {
+            kind,
+            kyc_id,
+            applicant_did: applicant_did.clone(),
+            sender,
+            registry_address,
+        });
+
+        // Call the DID Registry Contract to check if the Sender has the right to act for a certain DID
+        // 0x05 is the Shortname for the method implemented on the Registry Contract, needs to be consistent
+        event_group_builder
+            .call(registry_address, Shortname::from_u32(0x05))
+            .argument(applicant_did)
+            .argument(sender)
+            .done();
+
+        event_group_builder.with_callback(callback).argument(request_id)
+    }
+
+    // Consumes the request answered by a DID Registry callback. The request fails if the answer is stale,
+    // the DID Registry refused, or `check` finds the request no longer applies; the failure is then
+    // recorded and its events are returned as the error
+    fn resolve_registry_check<F>(
+        &mut self,
+        request_id: u64,
+        success: bool,
+        resolved_at: i64,
+        check: F,
+    ) -> Result<PendingRequest, Vec<EventGroup>>
+    where
+        F: FnOnce(&ContractState, &PendingRequest) -> Option<&'static str>,
+    {
+        let request = self.take_request(request_id);
+        let failure_reason = self.stale_reason(&request).or_else(|| {
+            if !success {
+                Some("DID Not Registered or Not Authorized!")
+            } else {
+                check(self, &request)
+            }
+        });
+        match failure_reason {
+            Some(reason) => Err(self.record_failure(request.failure(reason, resolved_at))),
+            None => Ok(request),
+        }
+    }
+
+    // Records a Reviewer vote and decides the KYC once the quorum is reached, nothing is changed on error
+    fn cast_vote(&mut self, reviewer: Address, decided_at: i64, kyc_idx: u128, decision: Decision) -> Result<(), &'static str> {
+        let threshold = self.approval_threshold;
+        let kyc_to_approve = self.kycs.get_mut(&kyc_idx).ok_or("KYC Not Found!")?;
+        if !decision.approve && decision.reason == (ReasonCode::None {}) {
+            return Err("Rejection Requires a Reason!");
+        }
+        if decision.note.as_ref().is_some_and(|note| note.len() > MAX_NOTE_LENGTH) {
+            return Err("Note Too Long!");
+        }
+        // Four-eyes rule, only the account that submitted the KYC is blocked. Other accounts
+        // controlling the Applicant DID are not known to this contract and can still vote
+        if kyc_to_approve.submitter == reviewer {
+            return Err("Submitter Cannot Review Own KYC!");
+        }
+        if kyc_to_approve.is_erased() {
+            return Err("KYC Data Erased!");
+        }
+        if kyc_to_approve.votes.iter().any(|vote| vote.reviewer == reviewer) {
+            return Err("Reviewer Already Voted!");
+        }
+        if !matches!(kyc_to_approve.status, KycStatus::Submitted {} | KycStatus::UnderReview {}) {
+            return Err("KYC Not Under Review!");
+        }
+
+        // The first vote moves a fresh submission into review
+        if kyc_to_approve.status == (KycStatus::Submitted {}) {
+            kyc_to_approve.transition_to(KycStatus::UnderReview {}, decided_at);
+        }
+
+        kyc_to_approve.votes.push(ReviewVote {
+            reviewer,
+            decided_at,
+            decision,
+        });
+        kyc_to_approve.apply_quorum(threshold, decided_at);
+
+        Ok(())
+    }
+
+    fn reviewer_count(&self) -> usize {
+        self.roles.values().filter(|roles| roles.contains(&Role::Reviewer {})).count()
+    }
+
+    fn check_vc_issuance(&self, now: i64, kyc_idx: u128, valid_since: i64, valid_until: i64) -> Result<(), &'static str> {
+        if self.storage_adddress.identifier == [0x00; 20] {
+            return Err("Please configure a valid VC Storage Address!");
+        }
+        let kyc = self.kycs.get(&kyc_idx).ok_or("KYC Not Found!")?;
+        if valid_since >= valid_until {
+            return Err("Invalid Validity Window!");
+        }
+        if valid_until <= now {
+            return Err("VC Validity Already Ended!");
+        }
+        if kyc.status != (KycStatus::Approved {}) {
+            return Err("KYC Not Approved!");
+        }
+        if kyc.is_erased() {
+            return Err("KYC Data Erased!");
+        }
+        Ok(())
+    }
+
+    fn assert_can_issue_vc(&self, context: &ContractContext, kyc_idx: u128, valid_since: i64, valid_until: i64) {
+        if let Err(reason) = self.check_vc_issuance(context.block_production_time, kyc_idx, valid_since, valid_until) {
+            panic!("{}", reason);
+        }
+    }
+
+    // Adds the upload of a new VC to the event group and records the VC as Pending on the KYC
+    fn add_vc_upload(
+        &mut self,
+        event_group_builder: &mut EventGroupBuilder,
+        request: VcRequest,
+        replaces: Option<u128>,
+    ) -> u128 {
+        let vc_id: u128 = self.next_vc_id;
+        self.next_vc_id += 1;
+
+        let storage_address = self.storage_adddress;
+        let kyc = self.kycs.get_mut(&request.kyc_idx).unwrap();
+
+        // Call the VC Storage Contract to Upload a VC for the Applicant
+        // 0x02 is the Shortname for the method implemented on the Registry Contract, needs to be consistent
+        /* Function Signature
+        #[action(shortname = 0x02)]
+            pub fn upload_vc(
+            context: ContractContext,
+            state: ContractState,
+            issuer_did: String,
+            vc_id: u128,
+            subject_did: String,
+            subject_info: Vec<SubjectInfo>,
+            valid_since: i64,
+            valid_until: i64,
+            descrption: String,
+            is_revoked: bool,
+            subject_info_root: [u8; 32],
+        )
+        */
+        event_group_builder
+            .call(storage_address, Shortname::from_u32(0x02))
+            .argument(request.issuer_did.clone())
+            .argument(vc_id)
+            .argument(kyc.applicant_did.clone())
+            .argument(kyc.applicant_info.vc_subject_info())
+            .argument(request.valid_since)
+            .argument(request.valid_until)
+            .argument(request.description)
+            .argument(false)
+            .argument(kyc.merkle_root)
+            .done();
+
+        if let Some(predecessor_vc_id) = replaces {
+            let predecessor = kyc.issued_vcs.iter_mut().find(|issued_vc| issued_vc.vc_id == predecessor_vc_id).unwrap();
+            predecessor.replaced_by = Some(vc_id);
+        }
+        kyc.issued_vcs.push(IssuedVc {
+            vc_id,
+            issuer_did: request.issuer_did,
+            valid_since: request.valid_since,
+            valid_until: request.valid_until,
+            storage_address,
+            status: VcStatus::Pending {},
+            replaces,
+            replaced_by: None,
+        });
+
+        vc_id
+    }
+
+    // Marks the Issued VCs as RevocationPending and revokes them on the VC Storage Contract they were uploaded to
+    fn revoke_vcs(&mut self, kyc_idx: u128, vc_ids: Vec<u128>, sender: Address) -> Vec<EventGroup> {
+        let kyc = self.kycs.get_mut(&kyc_idx).unwrap();
+        let mut event_group_builder = EventGroup::builder();
+        let mut revoked_ids: Vec<u128> = vec![];
+
+        for issued_vc in kyc.issued_vcs.iter_mut() {
+            if issued_vc.status != (VcStatus::Issued {}) || !vc_ids.contains(&issued_vc.vc_id) {
+                continue;
+            }
+            issued_vc.status = VcStatus::RevocationPending {};
+            revoked_ids.push(issued_vc.vc_id);
+
+            // 0x03 is the Shortname for the method implemented on the VC Storage Contract, needs to be consistent
+            /* Function Signature
+            #[action(shortname = 0x03)]
+                pub fn revoke_vc(
+                context: ContractContext,
+                state: ContractState,
+                vc_id: u128,
+            )
+            */
+            event_group_builder
+                .call(issued_vc.storage_address, Shortname::from_u32(0x03))
+                .argument(issued_vc.vc_id)
+                .done();
+        }
+
+        if revoked_ids.is_empty() {
+            return vec![];
+        }
+
+        event_group_builder
+            .with_callback(SHORTNAME_REVOKE_VC_CALLBACK)
+            .argument(kyc_idx)
+            .argument(revoked_ids)
+            .argument(sender)
+            .done();
+
+        vec![event_group_builder.build()]
+    }
+
+    // Settles a VC upload the VC Storage Contract has answered. A successful upload is revoked again
+    // if the KYC stopped being approved in the meantime, and so is its predecessor when asked to.
+    // A failed upload frees its predecessor so the renewal can be retried and returns the failure
+    fn resolve_vc_upload(
+        &mut self,
+        kyc_idx: u128,
+        vc_id: u128,
+        succeeded: bool,
+        sender: Address,
+        revoke_predecessor: bool,
+        resolved_at: i64,
+    ) -> Result<Vec<EventGroup>, FailedSubmission> {
+        let kyc = self.kycs.get_mut(&kyc_idx).unwrap();
+        let kyc_approved = kyc.status == (KycStatus::Approved {});
+        let issued_vc = kyc.issued_vcs.iter_mut().find(|issued_vc| issued_vc.vc_id == vc_id).unwrap();
+        let replaces = issued_vc.replaces;
+
+        if succeeded {
+            issued_vc.status = VcStatus::Issued {};
+            let mut vc_ids_to_revoke: Vec<u128> = vec![];
+            // The KYC may have been revoked or expired while the upload was in flight
+            if !kyc_approved {
+                vc_ids_to_revoke.push(vc_id);
+            }
+            if let (Some(predecessor_vc_id), true) = (replaces, revoke_predecessor) {
+                vc_ids_to_revoke.push(predecessor_vc_id);
+            }
+            return Ok(self.revoke_vcs(kyc_idx, vc_ids_to_revoke, sender));
+        }
+
+        issued_vc.status = VcStatus::Failed {};
+        if let Some(predecessor_vc_id) = replaces {
+            if let Some(predecessor) = kyc.issued_vcs.iter_mut().find(|issued_vc| issued_vc.vc_id == predecessor_vc_id) {
+                predecessor.replaced_by = None;
+            }
+        }
+        Err(FailedSubmission {
+            kind: RequestKind::VcUpload {},
+            kyc_id: Some(kyc_idx),
+            applicant_did: kyc.applicant_did.clone(),
+            sender,
+            reason: "VC Storage Rejected the Upload!".to_string(),
+            failed_at: resolved_at,
+        })
+    }
+
+    // Keeps the failure in state and returns it to the caller, instead of reverting the callback
+    fn record_failure(&mut self, failure: FailedSubmission) -> Vec<EventGroup> {
+        let mut event_group_builder = EventGroup::builder();
+        event_group_builder.return_data(failure.clone());
+        self.log_failure(failure);
+
+        vec![event_group_builder.build()]
+    }
+
+    fn log_failure(&mut self, failure: FailedSubmission) {
+        if self.failed_submissions.len() >= MAX_FAILED_SUBMISSIONS {
+            self.failed_submissions.remove(0);
+        }
+        self.failed_submissions.push(failure);
+    }
+
+    // Erases the Applicant data and revokes the Issued VCs, which still carry it on the VC Storage Contract
+    fn erase_kyc(&mut self, kyc_idx: u128, sender: Address) -> Vec<EventGroup> {
+        let kyc = self.kycs.get_mut(&kyc_idx).unwrap();
+        kyc.erase_applicant_data();
+        let vc_ids = kyc.issued_vc_ids();
+
+        self.revoke_vcs(kyc_idx, vc_ids, sender)
+    }
+
+    fn unindex_kyc(&mut self, applicant_did: &str, kyc_id: u128) {
+        let mut now_empty = false;
+        if let Some(kyc_ids) = self.kycs_by_did.get_mut(applicant_did) {
+            kyc_ids.retain(|indexed_id| *indexed_id != kyc_id);
+            now_empty = kyc_ids.is_empty();
+        }
+        if now_empty {
+            self.kycs_by_did.remove(applicant_did);
+        }
+    }
+
+    fn index_kyc(&mut self, applicant_did: String, kyc_id: u128) {
+        match self.kycs_by_did.get_mut(&applicant_did) {
+            Some(kyc_ids) => kyc_ids.push(kyc_id),
+            None => {
+                self.kycs_by_did.insert(applicant_did, vec![kyc_id]);
+            }
+        }
+    }
 }
 
 #[init]
@@ -28,11 +506,27 @@ fn initialize(
 
     let kyc_storage: SortedVecMap<u128, Kyc> = SortedVecMap::new();
     let blank_address: Address = Address { address_type: AddressType::Account, identifier: [0x00; 20] };
+    let mut roles: SortedVecMap<Address, Vec<Role>> = SortedVecMap::new();
+    roles.insert(ctx.sender, vec![Role::Admin {}, Role::Reviewer {}, Role::Issuer {}]);
     let state = ContractState {
         owner: ctx.sender,
+        pending_owner: None,
         registry_address: blank_address,
         storage_adddress: blank_address,
         kycs: kyc_storage,
+        next_kyc_id: 0,
+        next_vc_id: 0,
+        kycs_by_did: SortedVecMap::new(),
+        roles,
+        approval_threshold: 1,
+        privacy_mode: false,
+        retention_period: 0,
+        encryption_keys: SortedVecMap::new(),
+        active_key_id: None,
+        next_key_id: 0,
+        failed_submissions: vec![],
+        pending_requests: SortedVecMap::new(),
+        next_request_id: 0,
     };
 
     state
@@ -41,9 +535,255 @@ fn initialize(
 #[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState)]
 pub struct Kyc {
     applicant_did: String,
-    applicant_info: Vec<SubjectInfo>,
-    approved: bool,
-    pending: bool,
+    submitter: Address, // Account that uploaded the KYC, verified against the DID Registry
+    applicant_info: ApplicantData,
+    merkle_root: [u8; 32], // Root over applicant_info, see merkle::merkle_root
+    status: KycStatus,
+    submitted_at: i64,
+    status_updated_at: i64, // Last status change or resubmission, used for retention
+    votes: Vec<ReviewVote>,
+    version: u32, // Starts at 1, incremented on every resubmission
+    history: Vec<KycVersion>, // Previous submissions and the votes cast on them, oldest first
+    discarded_votes: Vec<ReviewVote>, // Votes cleared by reset_votes, oldest first
+    issued_vcs: Vec<IssuedVc>, // VCs created from this KYC, oldest first
+    revocation: Option<KycRevocation>, // Set once an approved KYC has been revoked
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct KycRevocation {
+    revoked_by: Address,
+    reason: ReasonCode,
+    note: Option<String>,
+    effective_at: i64, // When the grounds for revocation applied, may precede recorded_at
+    recorded_at: i64,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct IssuedVc {
+    vc_id: u128,
+    issuer_did: String,
+    valid_since: i64,
+    valid_until: i64,
+    storage_address: Address, // VC Storage Contract the VC was uploaded to
+    status: VcStatus,
+    replaces: Option<u128>, // VC this one renewed
+    replaced_by: Option<u128>, // Renewal of this VC, set once the renewal has been sent
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VcStatus {
+    // Upload sent, waiting for the VC Storage Contract
+    #[discriminant(0)]
+    Pending {},
+    #[discriminant(1)]
+    Issued {},
+    #[discriminant(2)]
+    Failed {},
+    // Revocation sent, waiting for the VC Storage Contract
+    #[discriminant(3)]
+    RevocationPending {},
+    #[discriminant(4)]
+    Revoked {},
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct KycVersion {
+    version: u32,
+    submitter: Address,
+    applicant_info: ApplicantData,
+    merkle_root: [u8; 32],
+    votes: Vec<ReviewVote>,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct ReviewVote {
+    reviewer: Address,
+    decided_at: i64,
+    decision: Decision,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct Decision {
+    approve: bool,
+    reason: ReasonCode,
+    note: Option<String>, // Free-text explanation from the Reviewer
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReasonCode {
+    // Used for approvals
+    #[discriminant(0)]
+    None {},
+    #[discriminant(1)]
+    IncompleteDocuments {},
+    #[discriminant(2)]
+    InvalidDocuments {},
+    #[discriminant(3)]
+    IdentityMismatch {},
+    #[discriminant(4)]
+    SanctionsMatch {},
+    #[discriminant(5)]
+    HighRisk {},
+    #[discriminant(6)]
+    Other {},
+}
+
+const MAX_NOTE_LENGTH: usize = 512;
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct KycDecision {
+    kyc_idx: u128,
+    decision: Decision,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct VcRequest {
+    kyc_idx: u128,
+    issuer_did: String,
+    valid_since: i64, // Unix milliseconds
+    valid_until: i64, // Unix milliseconds
+    description: String,
+}
+
+// A VC sent to the VC Storage Contract as part of a batch
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct BatchVc {
+    kyc_idx: u128,
+    vc_id: u128,
+}
+
+// Per-item result of a batch action, returned to the caller
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct BatchOutcome {
+    kyc_idx: u128,
+    vc_id: Option<u128>,
+    success: bool,
+    reason: Option<String>,
+}
+
+impl BatchOutcome {
+    fn new(kyc_idx: u128, vc_id: Option<u128>, result: Result<(), &str>) -> Self {
+        BatchOutcome {
+            kyc_idx,
+            vc_id,
+            success: result.is_ok(),
+            reason: result.err().map(|reason| reason.to_string()),
+        }
+    }
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KycStatus {
+    #[discriminant(0)]
+    Submitted {},
+    #[discriminant(1)]
+    UnderReview {},
+    #[discriminant(2)]
+    Approved {},
+    #[discriminant(3)]
+    Rejected {},
+    #[discriminant(4)]
+    Expired {},
+    #[discriminant(5)]
+    Revoked {},
+    #[discriminant(6)]
+    Withdrawn {},
+}
+
+impl KycStatus {
+    // Records that never led to a credential, these are dropped once past retention
+    fn is_prunable(&self) -> bool {
+        matches!(
+            self,
+            KycStatus::Submitted {} | KycStatus::UnderReview {} | KycStatus::Rejected {} | KycStatus::Withdrawn {}
+        )
+    }
+
+    // Only one active KYC may exist per Applicant DID at any time
+    fn is_active(&self) -> bool {
+        matches!(self, KycStatus::Submitted {} | KycStatus::UnderReview {} | KycStatus::Approved {})
+    }
+
+    // Allowed lifecycle transitions, every status change on a KYC has to go through here except
+    // the return of a rejected KYC into review, which only a resubmission may do, see Kyc::reopen
+    fn can_transition_to(&self, next: KycStatus) -> bool {
+        matches!(
+            (self, next),
+            (KycStatus::Submitted {}, KycStatus::UnderReview {})
+                | (KycStatus::Submitted {}, KycStatus::Approved {})
+                | (KycStatus::Submitted {}, KycStatus::Rejected {})
+                | (KycStatus::Submitted {}, KycStatus::Withdrawn {})
+                | (KycStatus::UnderReview {}, KycStatus::Approved {})
+                | (KycStatus::UnderReview {}, KycStatus::Rejected {})
+                | (KycStatus::UnderReview {}, KycStatus::Withdrawn {})
+                | (KycStatus::Approved {}, KycStatus::Expired {})
+                | (KycStatus::Approved {}, KycStatus::Revoked {})
+        )
+    }
+}
+
+impl Kyc {
+    fn transition_to(&mut self, next: KycStatus, at: i64) {
+        assert!(self.status.can_transition_to(next), "Invalid KYC Status Transition!");
+        self.status = next;
+        self.status_updated_at = at;
+    }
+
+    // Puts a rejected KYC back into review, only used once a resubmission has passed the DID Registry check
+    fn reopen(&mut self, at: i64) {
+        assert!(self.status == (KycStatus::Rejected {}), "Only Rejected KYCs Can Be Resubmitted!");
+        self.status = KycStatus::UnderReview {};
+        self.status_updated_at = at;
+    }
+
+    // Decides the KYC once either side reaches the quorum. If both sides reach it, which only
+    // happens after the threshold was lowered, the KYC stays under review until its votes are reset
+    fn apply_quorum(&mut self, threshold: u32, at: i64) {
+        if self.status != (KycStatus::UnderReview {}) {
+            return;
+        }
+        let approvals = self.votes.iter().filter(|vote| vote.decision.approve).count() as u32;
+        let rejections = self.votes.len() as u32 - approvals;
+        if approvals >= threshold && rejections >= threshold {
+            return;
+        }
+        if approvals >= threshold {
+            self.transition_to(KycStatus::Approved {}, at);
+        } else if rejections >= threshold {
+            self.transition_to(KycStatus::Rejected {}, at);
+        }
+    }
+
+    // A VC that is issued or on its way, issuing another one would duplicate it
+    fn has_live_vc(&self) -> bool {
+        self.issued_vcs
+            .iter()
+            .any(|issued_vc| matches!(issued_vc.status, VcStatus::Pending {} | VcStatus::Issued {}))
+    }
+
+    fn has_pending_vc_upload(&self) -> bool {
+        self.issued_vcs.iter().any(|issued_vc| issued_vc.status == (VcStatus::Pending {}))
+    }
+
+    fn issued_vc_ids(&self) -> Vec<u128> {
+        self.issued_vcs
+            .iter()
+            .filter(|issued_vc| issued_vc.status == (VcStatus::Issued {}))
+            .map(|issued_vc| issued_vc.vc_id)
+            .collect()
+    }
+
+    fn is_erased(&self) -> bool {
+        matches!(self.applicant_info, ApplicantData::Erased { .. })
+    }
+
+    // Wipes the Applicant data of every version, keeping only a hash of what was erased
+    fn erase_applicant_data(&mut self) {
+        self.applicant_info = self.applicant_info.erased();
+        for previous in self.history.iter_mut() {
+            previous.applicant_info = previous.applicant_info.erased();
+        }
+    }
 }
 
 #[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
@@ -52,6 +792,95 @@ pub struct SubjectInfo {
     property_value: String,
 }
 
+impl SubjectInfo {
+    pub fn new(property_name: String, property_value: String) -> Self {
+        SubjectInfo { property_name, property_value }
+    }
+}
+
+// Salted hash commitment of a single SubjectInfo, see commitment::commit_subject_info
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct SubjectCommitment {
+    property_name: String,
+    commitment: [u8; 32],
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub enum ApplicantData {
+    // Property values stored in cleartext
+    #[discriminant(0)]
+    Plain { info: Vec<SubjectInfo> },
+    // Only commitments are stored, the salts and values stay with the Applicant
+    #[discriminant(1)]
+    Committed { commitments: Vec<SubjectCommitment> },
+    // Values encrypted to a registered compliance key, commitments keep the Merkle root stable across re-encryption
+    #[discriminant(2)]
+    Encrypted { key_id: u32, fields: Vec<EncryptedSubjectInfo> },
+    // Data removed on request, the hash proves what had been stored
+    #[discriminant(3)]
+    Erased { content_hash: [u8; 32] },
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct EncryptedSubjectInfo {
+    property_name: String,
+    commitment: [u8; 32],
+    ciphertext: Vec<u8>,
+}
+
+impl ApplicantData {
+    fn erased(&self) -> ApplicantData {
+        match self {
+            ApplicantData::Erased { content_hash } => ApplicantData::Erased { content_hash: *content_hash },
+            _ => ApplicantData::Erased { content_hash: commitment::content_hash(self) },
+        }
+    }
+
+    // What gets forwarded to the VC Storage Contract, commitments are passed as hex strings
+    fn vc_subject_info(&self) -> Vec<SubjectInfo> {
+        match self {
+            ApplicantData::Plain { info } => info.clone(),
+            ApplicantData::Committed { commitments } => commitments
+                .iter()
+                .map(|c| SubjectInfo {
+                    property_name: c.property_name.clone(),
+                    property_value: commitment::to_hex(&c.commitment),
+                })
+                .collect(),
+            ApplicantData::Encrypted { fields, .. } => fields
+                .iter()
+                .map(|field| SubjectInfo {
+                    property_name: field.property_name.clone(),
+                    property_value: commitment::to_hex(&field.commitment),
+                })
+                .collect(),
+            ApplicantData::Erased { .. } => vec![],
+        }
+    }
+
+    fn merkle_leaves(&self) -> Vec<[u8; 32]> {
+        match self {
+            ApplicantData::Plain { info } => info
+                .iter()
+                .map(|subject_info| merkle::subject_leaf(subject_info, &[0x00; 32]))
+                .collect(),
+            ApplicantData::Committed { commitments } => commitments
+                .iter()
+                .map(|c| merkle::leaf_from_commitment(&c.commitment))
+                .collect(),
+            ApplicantData::Encrypted { fields, .. } => fields
+                .iter()
+                .map(|field| merkle::leaf_from_commitment(&field.commitment))
+                .collect(),
+            ApplicantData::Erased { .. } => vec![],
+        }
+    }
+
+    fn merkle_root(&self) -> [u8; 32] {
+        merkle::merkle_root(&self.merkle_leaves())
+    }
+}
+
 
 #[action(shortname = 0x01)]
 pub fn configure_registry_address(
@@ -61,7 +890,7 @@ pub fn configure_registry_address(
     target_storage_address: Address,
 ) -> ContractState {
 
-    assert!(context.sender == state.owner, "Not Authorized!");
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
 
     state.registry_address = target_registry_address;
     state.storage_adddress = target_storage_address;
@@ -77,26 +906,68 @@ pub fn upload_kyc(
     applicant_info: Vec<SubjectInfo>,
 ) -> (ContractState, Vec<EventGroup>) {
 
-    assert!(state.registry_address.identifier != [0x00; 20], "Please configure a valid DID Registry Address!");
+    submit_kyc(context, state, applicant_did, ApplicantData::Plain { info: applicant_info })
+}
+
+#[action(shortname = 0x0E)]
+pub fn upload_kyc_committed(
+    context: ContractContext,
+    state: ContractState,
+    applicant_did: String,
+    applicant_commitments: Vec<SubjectCommitment>,
+) -> (ContractState, Vec<EventGroup>) {
+
+    submit_kyc(context, state, applicant_did, ApplicantData::Committed { commitments: applicant_commitments })
+}
+
+#[action(shortname = 0x11)]
+pub fn upload_kyc_encrypted(
+    context: ContractContext,
+    state: ContractState,
+    applicant_did: String,
+    key_id: u32,
+    applicant_fields: Vec<EncryptedSubjectInfo>,
+) -> (ContractState, Vec<EventGroup>) {
+
+    submit_kyc(context, state, applicant_did, ApplicantData::Encrypted { key_id, fields: applicant_fields })
+}
+
+fn submit_kyc(
+    context: ContractContext,
+    mut state: ContractState,
+    applicant_did: String,
+    applicant_info: ApplicantData,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(!state.has_active_kyc(&applicant_did), "Active KYC Already Exists for DID!");
+    state.assert_accepts(&applicant_info);
 
     let mut event_group_builder = EventGroup::builder();
     let copied_did = applicant_did.clone();
 
     let new_kyc : Kyc = Kyc { 
         applicant_did: applicant_did,
+        submitter: context.sender,
+        merkle_root: applicant_info.merkle_root(),
         applicant_info: applicant_info, 
-        approved: false, 
-        pending: true, };
-    // Call the DID Registry Contract to check if the Sender has the right to upload KVC for a certain DID
-    // 0x05 is the Shortname for the method implemented on the Registry Contract, needs to be consistent
-    event_group_builder
-    .call(state.registry_address, Shortname::from_u32(0x05))
-    .argument(copied_did)
-    .argument(context.sender)
-    .done();
-
-    event_group_builder
-        .with_callback(SHORTNAME_UPLOAD_KYC_CALLBACK)
+        status: KycStatus::Submitted {},
+        submitted_at: context.block_production_time,
+        status_updated_at: context.block_production_time,
+        votes: vec![],
+        version: 1,
+        history: vec![],
+        discarded_votes: vec![],
+        issued_vcs: vec![],
+        revocation: None, };
+    state
+        .check_registry(
+            &mut event_group_builder,
+            RequestKind::Upload {},
+            None,
+            copied_did,
+            context.sender,
+            SHORTNAME_UPLOAD_KYC_CALLBACK,
+        )
         .argument(new_kyc)
         .done();
 
@@ -106,106 +977,903 @@ pub fn upload_kyc(
 
 #[callback(shortname = 0x12)]
 pub fn upload_kyc_callback(
-    _context: ContractContext,
+    context: ContractContext,
     callback_context: CallbackContext,
     mut state: ContractState,
+    request_id: u64,
     new_kyc: Kyc,
 ) -> (ContractState, Vec<EventGroup>) {
-    assert!(callback_context.success, "DID Not Registered or Not Authorized!");
+    // Re-check, another upload for the same DID may have landed while this one was in flight
+    let resolved = state.resolve_registry_check(
+        request_id,
+        callback_context.success,
+        context.block_production_time,
+        |state, _| {
+            if state.has_active_kyc(&new_kyc.applicant_did) {
+                Some("Active KYC Already Exists for DID!")
+            } else {
+                state.rejection_reason(&new_kyc.applicant_info)
+            }
+        },
+    );
+    if let Err(events) = resolved {
+        return (state, events);
+    }
 
-    let current_idx: u128 = state.kycs.len().try_into().unwrap();
-    state.kycs.insert(current_idx, new_kyc);
+    let kyc_id: u128 = state.next_kyc_id;
+    state.next_kyc_id += 1;
+    state.index_kyc(new_kyc.applicant_did.clone(), kyc_id);
+    state.kycs.insert(kyc_id, new_kyc);
 
-    (state, vec![])
+    // Return the assigned KYC ID so the caller can reference the new KYC
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(kyc_id);
+
+    (state, vec![event_group_builder.build()])
 }
 
-#[action(shortname = 0x03)]
-pub fn approve_kyc(
+#[action(shortname = 0x0C)]
+pub fn resubmit_kyc(
     context: ContractContext,
-    mut state: ContractState,
+    state: ContractState,
     kyc_idx: u128,
-    decision: bool,
-) -> ContractState {
-
-    assert!(context.sender == state.owner, "Not Authorized!");
-    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    applicant_info: Vec<SubjectInfo>,
+) -> (ContractState, Vec<EventGroup>) {
 
-    let kyc_to_approve = state.kycs.get_mut(&kyc_idx).unwrap();
-    kyc_to_approve.pending = false;
+    resubmit(context, state, kyc_idx, ApplicantData::Plain { info: applicant_info })
+}
 
-    if decision {
-        kyc_to_approve.approved = true;
-    } else {
-        kyc_to_approve.approved = false;
-    }
+#[action(shortname = 0x21)]
+pub fn resubmit_kyc_committed(
+    context: ContractContext,
+    state: ContractState,
+    kyc_idx: u128,
+    applicant_commitments: Vec<SubjectCommitment>,
+) -> (ContractState, Vec<EventGroup>) {
 
-    state
+    resubmit(context, state, kyc_idx, ApplicantData::Committed { commitments: applicant_commitments })
 }
 
-#[action(shortname = 0x04)]
-pub fn create_vc(
+#[action(shortname = 0x22)]
+pub fn resubmit_kyc_encrypted(
     context: ContractContext,
     state: ContractState,
     kyc_idx: u128,
-    issuer_did: String,
-    valid_since: String,
-    valid_until: String,
-    description: String,
+    key_id: u32,
+    applicant_fields: Vec<EncryptedSubjectInfo>,
+) -> (ContractState, Vec<EventGroup>) {
+
+    resubmit(context, state, kyc_idx, ApplicantData::Encrypted { key_id, fields: applicant_fields })
+}
+
+fn resubmit(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    applicant_info: ApplicantData,
 ) -> (ContractState, Vec<EventGroup>) {
 
-    assert!(context.sender == state.owner, "Not Authorized!");
-    assert!(state.storage_adddress.identifier != [0x00; 20], "Please configure a valid VC Storage Address!");
     assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
-    assert!(state.kycs.get(&kyc_idx).unwrap().approved, "KYC Not Approved!");
 
     let kyc = state.kycs.get(&kyc_idx).unwrap();
-    let mut event_group_builder = EventGroup::builder();
-    let copied_issuer_did = issuer_did.clone();
-    let copied_applicant_did = kyc.applicant_did.clone();
-
-    // Call the VC Storage Contract to Upload a VC for the Applicant
-    // 0x02 is the Shortname for the method implemented on the Registry Contract, needs to be consistent
-    /* Function Signature
-    #[action(shortname = 0x02)]
-        pub fn upload_vc(
-        context: ContractContext,
-        state: ContractState,
-        issuer_did: String,
-        vc_id: u128,
-        subject_did: String,
-        subject_info: Vec<SubjectInfo>,
-        valid_since: String,
-        valid_until: String,
-        descrption: String,
-        is_revoked: bool,
-    )
-    */
-    event_group_builder
-        .call(state.storage_adddress, Shortname::from_u32(0x02))
-        .argument(copied_issuer_did)
-        .argument(kyc_idx)
-        .argument(copied_applicant_did)
-        .argument(kyc.applicant_info.clone())
-        .argument(valid_since)
-        .argument(valid_until)
-        .argument(description)
-        .argument(false)
-        .done();
+    assert!(kyc.status == (KycStatus::Rejected {}), "Only Rejected KYCs Can Be Resubmitted!");
+    assert!(!state.has_active_kyc(&kyc.applicant_did), "Active KYC Already Exists for DID!");
+    state.assert_accepts(&applicant_info);
 
-    event_group_builder
-        .with_callback(SHORTNAME_CREATE_VC_CALLBACK)
+    let applicant_did = kyc.applicant_did.clone();
+    let mut event_group_builder = EventGroup::builder();
+    state
+        .check_registry(
+            &mut event_group_builder,
+            RequestKind::Resubmission {},
+            Some(kyc_idx),
+            applicant_did,
+            context.sender,
+            SHORTNAME_RESUBMIT_KYC_CALLBACK,
+        )
+        .argument(applicant_info)
         .done();
 
     (state, vec![event_group_builder.build()])
 }
 
-#[callback(shortname = 0x14)]
-pub fn create_vc_callback(
-    _context: ContractContext,
+#[callback(shortname = 0x1C)]
+pub fn resubmit_kyc_callback(
+    context: ContractContext,
     callback_context: CallbackContext,
-    state: ContractState,
+    mut state: ContractState,
+    request_id: u64,
+    applicant_info: ApplicantData,
 ) -> (ContractState, Vec<EventGroup>) {
-    assert!(callback_context.success, "VC Failed to Upload!");
+    let resolved = state.resolve_registry_check(
+        request_id,
+        callback_context.success,
+        context.block_production_time,
+        |state, request| match state.kycs.get(&request.kyc_id.unwrap()) {
+            None => Some("KYC Not Found!"),
+            // Re-check, the DID may have gained an active KYC or the KYC moved on while this one was in flight
+            Some(kyc) if state.has_active_kyc(&kyc.applicant_did) => Some("Active KYC Already Exists for DID!"),
+            Some(kyc) if kyc.status != (KycStatus::Rejected {}) => Some("Only Rejected KYCs Can Be Resubmitted!"),
+            Some(_) => state.rejection_reason(&applicant_info),
+        },
+    );
+    let request = match resolved {
+        Ok(request) => request,
+        Err(events) => return (state, events),
+    };
+    let kyc_idx = request.kyc_id.unwrap();
 
-    (state, vec![])
-}
\ No newline at end of file
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    kyc.reopen(context.block_production_time);
+
+    // Keep the previous version and its decision for audit
+    let previous_root = std::mem::replace(&mut kyc.merkle_root, applicant_info.merkle_root());
+    let previous_info = std::mem::replace(&mut kyc.applicant_info, applicant_info);
+    let previous_votes = std::mem::take(&mut kyc.votes);
+    kyc.history.push(KycVersion {
+        version: kyc.version,
+        submitter: kyc.submitter,
+        applicant_info: previous_info,
+        merkle_root: previous_root,
+        votes: previous_votes,
+    });
+    kyc.version += 1;
+    kyc.submitter = request.sender;
+
+    (state, vec![])
+}
+
+#[action(shortname = 0x0D)]
+pub fn withdraw_kyc(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+
+    let kyc = state.kycs.get(&kyc_idx).unwrap();
+    assert!(kyc.status.can_transition_to(KycStatus::Withdrawn {}), "KYC Cannot Be Withdrawn!");
+
+    let applicant_did = kyc.applicant_did.clone();
+    let mut event_group_builder = EventGroup::builder();
+    state
+        .check_registry(
+            &mut event_group_builder,
+            RequestKind::Withdrawal {},
+            Some(kyc_idx),
+            applicant_did,
+            context.sender,
+            SHORTNAME_WITHDRAW_KYC_CALLBACK,
+        )
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[callback(shortname = 0x1D)]
+pub fn withdraw_kyc_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: ContractState,
+    request_id: u64,
+) -> (ContractState, Vec<EventGroup>) {
+    let resolved = state.resolve_registry_check(
+        request_id,
+        callback_context.success,
+        context.block_production_time,
+        |state, request| match state.kycs.get(&request.kyc_id.unwrap()) {
+            None => Some("KYC Not Found!"),
+            // The KYC may have been decided while the withdrawal was in flight
+            Some(kyc) if !kyc.status.can_transition_to(KycStatus::Withdrawn {}) => Some("KYC Cannot Be Withdrawn!"),
+            Some(_) => None,
+        },
+    );
+    let request = match resolved {
+        Ok(request) => request,
+        Err(events) => return (state, events),
+    };
+    let kyc_idx = request.kyc_id.unwrap();
+
+    state.kycs.get_mut(&kyc_idx).unwrap().transition_to(KycStatus::Withdrawn {}, context.block_production_time);
+
+    (state, vec![])
+}
+
+// Right to erasure, Admins erase directly, anyone else has to prove control of the Applicant DID
+#[action(shortname = 0x15)]
+pub fn erase_kyc_data(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    assert!(!state.kycs.get(&kyc_idx).unwrap().is_erased(), "KYC Data Already Erased!");
+    // The upload would land the data on the VC Storage Contract after it has been erased here
+    assert!(!state.kycs.get(&kyc_idx).unwrap().has_pending_vc_upload(), "VC Upload In Flight!");
+
+    if state.has_role(&context.sender, Role::Admin {}) {
+        let events = state.erase_kyc(kyc_idx, context.sender);
+        return (state, events);
+    }
+
+    let applicant_did = state.kycs.get(&kyc_idx).unwrap().applicant_did.clone();
+    let mut event_group_builder = EventGroup::builder();
+    state
+        .check_registry(
+            &mut event_group_builder,
+            RequestKind::Erasure {},
+            Some(kyc_idx),
+            applicant_did,
+            context.sender,
+            SHORTNAME_ERASE_KYC_DATA_CALLBACK,
+        )
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[callback(shortname = 0x25)]
+pub fn erase_kyc_data_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: ContractState,
+    request_id: u64,
+) -> (ContractState, Vec<EventGroup>) {
+    let resolved = state.resolve_registry_check(
+        request_id,
+        callback_context.success,
+        context.block_production_time,
+        |state, request| match state.kycs.get(&request.kyc_id.unwrap()) {
+            None => Some("KYC Not Found!"),
+            // A VC may have been created while the erasure was in flight
+            Some(kyc) if kyc.has_pending_vc_upload() => Some("VC Upload In Flight!"),
+            Some(_) => None,
+        },
+    );
+    let request = match resolved {
+        Ok(request) => request,
+        Err(events) => return (state, events),
+    };
+
+    let events = state.erase_kyc(request.kyc_id.unwrap(), request.sender);
+
+    (state, events)
+}
+
+#[action(shortname = 0x03)]
+pub fn approve_kyc(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    decision: Decision,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Reviewer {}), "Not Authorized!");
+
+    if let Err(reason) = state.cast_vote(context.sender, context.block_production_time, kyc_idx, decision) {
+        panic!("{}", reason);
+    }
+
+    state
+}
+
+// Invalid items are skipped and reported instead of reverting the whole batch
+#[action(shortname = 0x1E)]
+pub fn approve_kyc_batch(
+    context: ContractContext,
+    mut state: ContractState,
+    decisions: Vec<KycDecision>,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Reviewer {}), "Not Authorized!");
+
+    let outcomes: Vec<BatchOutcome> = decisions
+        .into_iter()
+        .map(|item| {
+            let result = state.cast_vote(context.sender, context.block_production_time, item.kyc_idx, item.decision);
+            BatchOutcome::new(item.kyc_idx, None, result)
+        })
+        .collect();
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(outcomes);
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[action(shortname = 0x05)]
+pub fn start_review(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Reviewer {}), "Not Authorized!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    // Rejected KYCs only return to review through a resubmission
+    assert!(kyc.status == (KycStatus::Submitted {}), "KYC Already Under Review or Decided!");
+    kyc.transition_to(KycStatus::UnderReview {}, context.block_production_time);
+
+    state
+}
+
+// Discards the votes of a KYC stuck under review, e.g. on a split vote, so it can be reviewed again.
+// The discarded votes are kept on the KYC for audit
+#[action(shortname = 0x20)]
+pub fn reset_votes(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
+    let kyc = state.kycs.get_mut(&kyc_idx).expect("KYC Not Found!");
+    assert!(kyc.status == (KycStatus::UnderReview {}), "KYC Not Under Review!");
+    assert!(!kyc.votes.is_empty(), "No Votes to Reset!");
+
+    kyc.discarded_votes.append(&mut kyc.votes);
+    kyc.status_updated_at = context.block_production_time;
+
+    state
+}
+
+#[action(shortname = 0x06)]
+pub fn grant_role(
+    context: ContractContext,
+    mut state: ContractState,
+    account: Address,
+    role: Role,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
+
+    match state.roles.get_mut(&account) {
+        Some(roles) => {
+            if !roles.contains(&role) {
+                roles.push(role);
+            }
+        }
+        None => {
+            state.roles.insert(account, vec![role]);
+        }
+    }
+
+    state
+}
+
+#[action(shortname = 0x07)]
+pub fn revoke_role(
+    context: ContractContext,
+    mut state: ContractState,
+    account: Address,
+    role: Role,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
+
+    let mut now_empty = false;
+    if let Some(roles) = state.roles.get_mut(&account) {
+        roles.retain(|granted| *granted != role);
+        now_empty = roles.is_empty();
+    }
+    if now_empty {
+        state.roles.remove(&account);
+    }
+    assert!(state.approval_threshold as usize <= state.reviewer_count(), "Approval Threshold Exceeds Number of Reviewers!");
+
+    state
+}
+
+#[action(shortname = 0x08)]
+pub fn propose_owner(
+    context: ContractContext,
+    mut state: ContractState,
+    new_owner: Address,
+) -> ContractState {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+    assert!(new_owner != state.owner, "Already the Owner!");
+
+    state.pending_owner = Some(new_owner);
+
+    state
+}
+
+#[action(shortname = 0x09)]
+pub fn accept_ownership(
+    context: ContractContext,
+    mut state: ContractState,
+) -> ContractState {
+
+    // Ownership only moves once the proposed address confirms it can sign
+    assert!(state.pending_owner == Some(context.sender), "Not Authorized!");
+
+    // The new owner takes over the roles granted at deployment, the previous owner keeps none
+    let previous_owner = state.owner;
+    state.roles.remove(&previous_owner);
+    state.roles.insert(context.sender, vec![Role::Admin {}, Role::Reviewer {}, Role::Issuer {}]);
+
+    state.owner = context.sender;
+    state.pending_owner = None;
+
+    state
+}
+
+#[action(shortname = 0x0A)]
+pub fn cancel_ownership_transfer(
+    context: ContractContext,
+    mut state: ContractState,
+) -> ContractState {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+    assert!(state.pending_owner.is_some(), "No Ownership Transfer Pending!");
+
+    state.pending_owner = None;
+
+    state
+}
+
+#[action(shortname = 0x0B)]
+pub fn set_approval_threshold(
+    context: ContractContext,
+    mut state: ContractState,
+    approval_threshold: u32,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
+    assert!(approval_threshold >= 1, "Approval Threshold Must Be at Least 1!");
+    assert!(approval_threshold as usize <= state.reviewer_count(), "Approval Threshold Exceeds Number of Reviewers!");
+
+    state.approval_threshold = approval_threshold;
+
+    // KYCs already under review are decided right away if their votes meet the new quorum
+    let under_review: Vec<u128> = state
+        .kycs
+        .iter()
+        .filter(|(_, kyc)| kyc.status == (KycStatus::UnderReview {}))
+        .map(|(kyc_id, _)| *kyc_id)
+        .collect();
+    for kyc_id in under_review {
+        state.kycs.get_mut(&kyc_id).unwrap().apply_quorum(approval_threshold, context.block_production_time);
+    }
+
+    state
+}
+
+#[action(shortname = 0x0F)]
+pub fn configure_privacy_mode(
+    context: ContractContext,
+    mut state: ContractState,
+    privacy_mode: bool,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
+
+    state.privacy_mode = privacy_mode;
+
+    state
+}
+
+#[action(shortname = 0x10)]
+pub fn register_encryption_key(
+    context: ContractContext,
+    mut state: ContractState,
+    public_key: Vec<u8>,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
+    assert!(!public_key.is_empty(), "Invalid Public Key!");
+
+    // The new key replaces the active one, older keys are kept so existing ciphertexts stay attributable
+    if let Some(previous_key_id) = state.active_key_id {
+        state.encryption_keys.get_mut(&previous_key_id).unwrap().retired_at = Some(context.block_production_time);
+    }
+
+    let key_id = state.next_key_id;
+    state.next_key_id += 1;
+    state.encryption_keys.insert(key_id, EncryptionKey {
+        public_key,
+        registered_at: context.block_production_time,
+        retired_at: None,
+    });
+    state.active_key_id = Some(key_id);
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(key_id);
+
+    (state, vec![event_group_builder.build()])
+}
+
+// Re-encryption submission after a key rotation, the compliance team decrypts with the old key
+// and uploads the same fields encrypted to the active key. Every version of the KYC, current or
+// in its history, has to be re-encrypted on its own for the rotation to be complete
+#[action(shortname = 0x13)]
+pub fn reencrypt_kyc(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    version: u32,
+    key_id: u32,
+    applicant_fields: Vec<EncryptedSubjectInfo>,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Reviewer {}), "Not Authorized!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    assert!(state.active_key_id == Some(key_id), "Encryption Key Not Active!");
+
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    let applicant_info = if version == kyc.version {
+        &mut kyc.applicant_info
+    } else {
+        &mut kyc
+            .history
+            .iter_mut()
+            .find(|previous| previous.version == version)
+            .expect("KYC Version Not Found!")
+            .applicant_info
+    };
+    match &*applicant_info {
+        ApplicantData::Encrypted { key_id: current_key_id, fields } => {
+            assert!(*current_key_id != key_id, "KYC Already Encrypted to This Key!");
+            // The plaintext must not change, which the unchanged commitments attest to
+            assert!(
+                fields.len() == applicant_fields.len()
+                    && fields.iter().zip(applicant_fields.iter()).all(|(current, updated)| {
+                        current.property_name == updated.property_name && current.commitment == updated.commitment
+                    }),
+                "Re-encrypted Fields Do Not Match!"
+            );
+        }
+        _ => panic!("KYC Not Encrypted!"),
+    }
+
+    *applicant_info = ApplicantData::Encrypted { key_id, fields: applicant_fields };
+
+    state
+}
+
+#[action(shortname = 0x17)]
+pub fn set_retention_period(
+    context: ContractContext,
+    mut state: ContractState,
+    retention_period: i64,
+) -> ContractState {
+
+    assert!(state.has_role(&context.sender, Role::Admin {}), "Not Authorized!");
+    assert!(retention_period >= 0, "Invalid Retention Period!");
+
+    state.retention_period = retention_period;
+
+    state
+}
+
+// Permissionless so anyone can keep state size and storage fees bounded
+#[action(shortname = 0x16)]
+pub fn prune_expired(
+    context: ContractContext,
+    mut state: ContractState,
+    max_records: u32,
+) -> ContractState {
+
+    assert!(state.retention_period > 0, "Retention Policy Not Configured!");
+
+    let cutoff = context.block_production_time - state.retention_period;
+    let expired: Vec<(u128, String)> = state
+        .kycs
+        .iter()
+        .filter(|(_, kyc)| kyc.status.is_prunable() && kyc.status_updated_at <= cutoff)
+        .take(max_records as usize)
+        .map(|(kyc_id, kyc)| (*kyc_id, kyc.applicant_did.clone()))
+        .collect();
+
+    for (kyc_id, applicant_did) in expired {
+        state.kycs.remove(&kyc_id);
+        state.unindex_kyc(&applicant_did, kyc_id);
+    }
+
+    state
+}
+
+#[action(shortname = 0x04)]
+pub fn create_vc(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    issuer_did: String,
+    valid_since: i64, // Unix milliseconds
+    valid_until: i64, // Unix milliseconds
+    description: String,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Issuer {}), "Not Authorized!");
+    state.assert_can_issue_vc(&context, kyc_idx, valid_since, valid_until);
+    assert!(!state.kycs.get(&kyc_idx).unwrap().has_live_vc(), "VC Already Issued for KYC!");
+
+    let mut event_group_builder = EventGroup::builder();
+    let request = VcRequest { kyc_idx, issuer_did, valid_since, valid_until, description };
+    let vc_id = state.add_vc_upload(&mut event_group_builder, request, None);
+
+    event_group_builder
+        .with_callback(SHORTNAME_CREATE_VC_CALLBACK)
+        .argument(kyc_idx)
+        .argument(vc_id)
+        .argument(context.sender)
+        .argument(false)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+// Issues a fresh VC with a new ID and validity window for an approved KYC, e.g. when the
+// predecessor is nearing its valid_until
+#[action(shortname = 0x1B)]
+pub fn renew_vc(
+    context: ContractContext,
+    mut state: ContractState,
+    predecessor_vc_id: u128,
+    renewal: VcRequest,
+    revoke_predecessor: bool, // Revoke the predecessor once the renewal has been issued
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Issuer {}), "Not Authorized!");
+    let kyc_idx = renewal.kyc_idx;
+    state.assert_can_issue_vc(&context, kyc_idx, renewal.valid_since, renewal.valid_until);
+
+    let predecessor = state
+        .kycs
+        .get(&kyc_idx)
+        .unwrap()
+        .issued_vcs
+        .iter()
+        .find(|issued_vc| issued_vc.vc_id == predecessor_vc_id)
+        .expect("Issued VC Not Found!");
+    assert!(predecessor.status == (VcStatus::Issued {}), "VC Not Issued!");
+    assert!(predecessor.replaced_by.is_none(), "VC Already Renewed!");
+
+    let mut event_group_builder = EventGroup::builder();
+    let vc_id = state.add_vc_upload(&mut event_group_builder, renewal, Some(predecessor_vc_id));
+
+    event_group_builder
+        .with_callback(SHORTNAME_CREATE_VC_CALLBACK)
+        .argument(kyc_idx)
+        .argument(vc_id)
+        .argument(context.sender)
+        .argument(revoke_predecessor)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[callback(shortname = 0x14)]
+pub fn create_vc_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    vc_id: u128,
+    sender: Address,
+    revoke_predecessor: bool,
+) -> (ContractState, Vec<EventGroup>) {
+    // Not asserting here, a failed upload is recorded so the VC can be issued again
+    let events = match state.resolve_vc_upload(
+        kyc_idx,
+        vc_id,
+        callback_context.success,
+        sender,
+        revoke_predecessor,
+        context.block_production_time,
+    ) {
+        Ok(events) => events,
+        Err(failure) => state.record_failure(failure),
+    };
+
+    (state, events)
+}
+
+#[action(shortname = 0x18)]
+pub fn revoke_vc(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    vc_id: u128,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Issuer {}), "Not Authorized!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    assert!(state.kycs.get(&kyc_idx).unwrap().issued_vc_ids().contains(&vc_id), "Issued VC Not Found!");
+
+    let events = state.revoke_vcs(kyc_idx, vec![vc_id], context.sender);
+
+    (state, events)
+}
+
+#[callback(shortname = 0x28)]
+pub fn revoke_vc_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    vc_ids: Vec<u128>,
+    sender: Address,
+) -> (ContractState, Vec<EventGroup>) {
+    // One result per revocation call, in the order the VCs were revoked
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    let applicant_did = kyc.applicant_did.clone();
+    let mut failed_ids: Vec<u128> = vec![];
+
+    for (vc_id, result) in vc_ids.iter().zip(callback_context.results.iter()) {
+        let issued_vc = kyc
+            .issued_vcs
+            .iter_mut()
+            .find(|issued_vc| issued_vc.vc_id == *vc_id && issued_vc.status == (VcStatus::RevocationPending {}))
+            .unwrap();
+        if result.succeeded {
+            issued_vc.status = VcStatus::Revoked {};
+        } else {
+            // Back to Issued so the revocation can be retried
+            issued_vc.status = VcStatus::Issued {};
+            failed_ids.push(*vc_id);
+        }
+    }
+
+    let mut events = vec![];
+    for vc_id in failed_ids {
+        events.extend(state.record_failure(FailedSubmission {
+            kind: RequestKind::VcRevocation {},
+            kyc_id: Some(kyc_idx),
+            applicant_did: applicant_did.clone(),
+            sender,
+            reason: format!("VC Storage Rejected the Revocation of VC {}!", vc_id),
+            failed_at: context.block_production_time,
+        }));
+    }
+
+    (state, events)
+}
+
+// Expiring an approved KYC revokes every VC issued from it
+#[action(shortname = 0x19)]
+pub fn expire_kyc(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Reviewer {}), "Not Authorized!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    kyc.transition_to(KycStatus::Expired {}, context.block_production_time);
+    let vc_ids = kyc.issued_vc_ids();
+
+    let events = state.revoke_vcs(kyc_idx, vc_ids, context.sender);
+
+    (state, events)
+}
+
+// Revocation after the fact, e.g. a sanctions list hit, revokes every VC issued from the KYC
+#[action(shortname = 0x1A)]
+pub fn revoke_kyc(
+    context: ContractContext,
+    mut state: ContractState,
+    kyc_idx: u128,
+    reason: ReasonCode,
+    note: Option<String>,
+    effective_at: i64,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Reviewer {}), "Not Authorized!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    assert!(reason != (ReasonCode::None {}), "Revocation Requires a Reason!");
+    assert!(note.as_ref().is_none_or(|note| note.len() <= MAX_NOTE_LENGTH), "Note Too Long!");
+    assert!(effective_at <= context.block_production_time, "Revocation Cannot Take Effect in the Future!");
+
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    kyc.transition_to(KycStatus::Revoked {}, context.block_production_time);
+    kyc.revocation = Some(KycRevocation {
+        revoked_by: context.sender,
+        reason,
+        note,
+        effective_at,
+        recorded_at: context.block_production_time,
+    });
+    let vc_ids = kyc.issued_vc_ids();
+
+    let events = state.revoke_vcs(kyc_idx, vc_ids, context.sender);
+
+    (state, events)
+}
+
+// One event group uploads every valid VC, invalid items are reported without being sent
+#[action(shortname = 0x1F)]
+pub fn create_vc_batch(
+    context: ContractContext,
+    mut state: ContractState,
+    requests: Vec<VcRequest>,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.has_role(&context.sender, Role::Issuer {}), "Not Authorized!");
+
+    let mut event_group_builder = EventGroup::builder();
+    let mut outcomes: Vec<BatchOutcome> = vec![];
+    let mut sent: Vec<BatchVc> = vec![];
+
+    for request in requests {
+        let check = state
+            .check_vc_issuance(context.block_production_time, request.kyc_idx, request.valid_since, request.valid_until)
+            .and_then(|_| {
+                // Also catches the same KYC appearing twice in the batch
+                if state.kycs.get(&request.kyc_idx).unwrap().has_live_vc() {
+                    return Err("VC Already Issued for KYC!");
+                }
+                Ok(())
+            });
+        if check.is_err() {
+            outcomes.push(BatchOutcome::new(request.kyc_idx, None, check));
+            continue;
+        }
+
+        let kyc_idx = request.kyc_idx;
+        let vc_id = state.add_vc_upload(&mut event_group_builder, request, None);
+        sent.push(BatchVc { kyc_idx, vc_id });
+    }
+
+    if sent.is_empty() {
+        event_group_builder.return_data(outcomes);
+        return (state, vec![event_group_builder.build()]);
+    }
+
+    event_group_builder
+        .with_callback(SHORTNAME_CREATE_VC_BATCH_CALLBACK)
+        .argument(sent)
+        .argument(outcomes)
+        .argument(context.sender)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[callback(shortname = 0x2F)]
+pub fn create_vc_batch_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: ContractState,
+    sent: Vec<BatchVc>,
+    mut outcomes: Vec<BatchOutcome>,
+    sender: Address,
+) -> (ContractState, Vec<EventGroup>) {
+    // One result per upload call, in the order the VCs were added to the batch
+    let mut events: Vec<EventGroup> = vec![];
+
+    for (batch_vc, result) in sent.iter().zip(callback_context.results.iter()) {
+        let resolved = state.resolve_vc_upload(
+            batch_vc.kyc_idx,
+            batch_vc.vc_id,
+            result.succeeded,
+            sender,
+            false,
+            context.block_production_time,
+        );
+        match resolved {
+            Ok(revocations) => {
+                outcomes.push(BatchOutcome::new(batch_vc.kyc_idx, Some(batch_vc.vc_id), Ok(())));
+                events.extend(revocations);
+            }
+            Err(failure) => {
+                outcomes.push(BatchOutcome::new(batch_vc.kyc_idx, Some(batch_vc.vc_id), Err(&failure.reason)));
+                // Reported through the outcomes below, so only logged here
+                state.log_failure(failure);
+            }
+        }
+    }
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(outcomes);
+    events.push(event_group_builder.build());
+
+    (state, events)
+}
@@ -0,0 +1,192 @@
+//! Merkle tree over Applicant attributes for selective disclosure
+//!
+//! Every attribute becomes a leaf, the root is stored on the KYC and passed to the VC Storage
+//! Contract. A holder can then disclose a single SubjectInfo together with an inclusion proof.
+
+use read_write_rpc_derive::ReadWriteRPC;
+use sha2::{Digest, Sha256};
+
+use crate::commitment::commit_subject_info;
+use crate::SubjectInfo;
+
+// Domain separation so a leaf can never be passed off as an inner node
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+#[derive(ReadWriteRPC, Clone, PartialEq, Eq, Debug)]
+pub struct ProofStep {
+    pub sibling: [u8; 32],
+    pub sibling_is_left: bool,
+}
+
+#[derive(ReadWriteRPC, Clone, PartialEq, Eq, Debug)]
+pub struct MerkleProof {
+    pub steps: Vec<ProofStep>,
+}
+
+// Leaves are built from the attribute commitment, plaintext attributes use an all-zero salt
+pub fn leaf_from_commitment(commitment: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(commitment);
+    hasher.finalize().into()
+}
+
+pub fn subject_leaf(info: &SubjectInfo, salt: &[u8; 32]) -> [u8; 32] {
+    leaf_from_commitment(&commit_subject_info(info, salt))
+}
+
+fn hash_nodes(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// Pairs up nodes level by level, an odd node at the end is carried up unchanged
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_nodes(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// The root of no leaves is all zeroes
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0x00; 32];
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+pub fn generate_proof(leaves: &[[u8; 32]], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut steps = vec![];
+    let mut level = leaves.to_vec();
+    let mut position = index;
+    while level.len() > 1 {
+        let sibling_position = position ^ 1;
+        if sibling_position < level.len() {
+            steps.push(ProofStep {
+                sibling: level[sibling_position],
+                sibling_is_left: sibling_position < position,
+            });
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+    Some(MerkleProof { steps })
+}
+
+pub fn verify_proof(root: &[u8; 32], leaf: &[u8; 32], proof: &MerkleProof) -> bool {
+    let computed = proof.steps.iter().fold(*leaf, |node, step| {
+        if step.sibling_is_left {
+            hash_nodes(&step.sibling, &node)
+        } else {
+            hash_nodes(&node, &step.sibling)
+        }
+    });
+    computed == *root
+}
+
+// Checks that a single disclosed attribute is part of the KYC with the given root
+pub fn verify_subject_info(root: &[u8; 32], info: &SubjectInfo, salt: &[u8; 32], proof: &MerkleProof) -> bool {
+    verify_proof(root, &subject_leaf(info, salt), proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<[u8; 32]> {
+        (0..count).map(|i| leaf_from_commitment(&[i; 32])).collect()
+    }
+
+    fn assert_all_proofs_verify(leaves: &[[u8; 32]]) {
+        let root = merkle_root(leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = generate_proof(leaves, index).unwrap();
+            assert!(verify_proof(&root, leaf, &proof), "proof for leaf {} does not verify", index);
+        }
+    }
+
+    #[test]
+    fn empty_tree_has_zero_root() {
+        assert_eq!(merkle_root(&[]), [0x00; 32]);
+        assert!(generate_proof(&[], 0).is_none());
+    }
+
+    #[test]
+    fn single_leaf_is_its_own_root() {
+        let leaves = leaves(1);
+        assert_eq!(merkle_root(&leaves), leaves[0]);
+        assert!(generate_proof(&leaves, 0).unwrap().steps.is_empty());
+        assert_all_proofs_verify(&leaves);
+    }
+
+    #[test]
+    fn two_leaves() {
+        let leaves = leaves(2);
+        assert_eq!(merkle_root(&leaves), hash_nodes(&leaves[0], &leaves[1]));
+        assert_all_proofs_verify(&leaves);
+    }
+
+    #[test]
+    fn three_leaves_carry_the_odd_leaf_up() {
+        let leaves = leaves(3);
+        let expected = hash_nodes(&hash_nodes(&leaves[0], &leaves[1]), &leaves[2]);
+        assert_eq!(merkle_root(&leaves), expected);
+        assert_eq!(generate_proof(&leaves, 2).unwrap().steps.len(), 1);
+        assert_all_proofs_verify(&leaves);
+    }
+
+    #[test]
+    fn five_leaves() {
+        let leaves = leaves(5);
+        let left = hash_nodes(&hash_nodes(&leaves[0], &leaves[1]), &hash_nodes(&leaves[2], &leaves[3]));
+        assert_eq!(merkle_root(&leaves), hash_nodes(&left, &leaves[4]));
+        assert_all_proofs_verify(&leaves);
+    }
+
+    #[test]
+    fn tampered_leaf_is_rejected() {
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves);
+        let proof = generate_proof(&leaves, 1).unwrap();
+        assert!(!verify_proof(&root, &leaves[2], &proof));
+        assert!(!verify_proof(&root, &leaf_from_commitment(&[0xFF; 32]), &proof));
+    }
+
+    #[test]
+    fn index_out_of_range_has_no_proof() {
+        assert!(generate_proof(&leaves(3), 3).is_none());
+    }
+
+    #[test]
+    fn disclosed_subject_info_verifies_against_root() {
+        let salt = [0x00; 32];
+        let infos = [
+            SubjectInfo::new("name".to_string(), "Alice".to_string()),
+            SubjectInfo::new("country".to_string(), "DK".to_string()),
+            SubjectInfo::new("birthdate".to_string(), "19900101".to_string()),
+        ];
+        let leaves: Vec<[u8; 32]> = infos.iter().map(|info| subject_leaf(info, &salt)).collect();
+        let root = merkle_root(&leaves);
+        let proof = generate_proof(&leaves, 1).unwrap();
+
+        assert!(verify_subject_info(&root, &infos[1], &salt, &proof));
+        let forged = SubjectInfo::new("country".to_string(), "US".to_string());
+        assert!(!verify_subject_info(&root, &forged, &salt, &proof));
+    }
+}
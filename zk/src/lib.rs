@@ -0,0 +1,796 @@
+//! pbc-kyc-zk
+//!
+//! Zero-knowledge variant of pbc-kyc. Sensitive property values are uploaded as secret-shared
+//! variables and never land in public state, Reviewers receive them by having the variables
+//! transferred to their account, which lets only them reconstruct the values off-chain.
+
+#[macro_use]
+extern crate pbc_contract_codegen;
+
+mod zk_compute;
+
+use pbc_contract_common::address::{Address, AddressType};
+use pbc_contract_common::context::{CallbackContext, ContractContext};
+use pbc_contract_common::events::EventGroup;
+use pbc_contract_common::shortname::Shortname;
+use pbc_contract_common::sorted_vec_map::SortedVecMap;
+use pbc_contract_common::zk::{AttestationId, SecretVarId, ZkInputDef, ZkState, ZkStateChange};
+use pbc_zk::Sbi128;
+use read_write_state_derive::ReadWriteState;
+use read_write_rpc_derive::ReadWriteRPC;
+use create_type_spec_derive::CreateTypeSpec;
+
+
+#[state]
+pub struct ContractState {
+    owner: Address,
+    registry_address: Address,
+    storage_adddress: Address, // Has to be a VC Storage Contract of its own, VC IDs are only unique per issuing contract
+    reviewers: Vec<Address>,
+    kycs: SortedVecMap<u128, Kyc>, // Key: KYC ID, Value: KYC
+    next_kyc_id: u128, // Monotonically increasing, IDs are never reused
+    next_vc_id: u128, // Monotonically increasing, every VC uploaded to the VC Storage Contract gets a fresh ID
+    failed_submissions: Vec<FailedSubmission>, // Most recent failed callbacks, oldest first
+    predicates: SortedVecMap<u32, Predicate>, // Key: Predicate ID, Value: Predicate
+    next_predicate_id: u32,
+    pending_evaluation: Option<PendingEvaluation>, // Only one ZK computation can run at a time
+}
+
+// Attached to every secret variable so it can be matched to its KYC
+#[derive(ReadWriteState, ReadWriteRPC, Clone)]
+pub struct SecretVarMetadata {
+    kyc_idx: u128,
+    property_name: String,
+}
+
+#[init(zk = true)]
+fn initialize(
+    ctx: ContractContext,
+    _zk_state: ZkState<SecretVarMetadata>,
+) -> ContractState {
+
+    let blank_address: Address = Address { address_type: AddressType::Account, identifier: [0x00; 20] };
+    let state = ContractState {
+        owner: ctx.sender,
+        registry_address: blank_address,
+        storage_adddress: blank_address,
+        reviewers: vec![ctx.sender],
+        kycs: SortedVecMap::new(),
+        next_kyc_id: 0,
+        next_vc_id: 0,
+        failed_submissions: vec![],
+        predicates: SortedVecMap::new(),
+        next_predicate_id: 0,
+        pending_evaluation: None,
+    };
+
+    state
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState)]
+pub struct Kyc {
+    applicant_did: String,
+    submitter: Address,
+    public_info: Vec<SubjectInfo>, // Non-sensitive properties, stored in cleartext
+    secret_properties: Vec<SecretProperty>, // Sensitive properties, only the variable IDs are public
+    reviewer: Option<Address>, // Reviewer the secret variables were transferred to
+    status: KycStatus,
+    predicate_results: Vec<PredicateResult>, // Attested outcomes of predicates evaluated on the secret properties
+    vc_id: Option<u128>, // Set once a VC upload has been sent, cleared again if the upload fails
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct SubjectInfo {
+    property_name: String,
+    property_value: String,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct SecretProperty {
+    property_name: String,
+    variable_id: SecretVarId,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct FailedSubmission {
+    kyc_id: Option<u128>, // Not known for failed uploads
+    applicant_did: String,
+    sender: Address,
+    reason: String,
+    failed_at: i64,
+}
+
+// Keeps the failure log, and with it the state size, bounded
+const MAX_FAILED_SUBMISSIONS: usize = 100;
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KycStatus {
+    #[discriminant(0)]
+    Submitted {},
+    #[discriminant(1)]
+    UnderReview {},
+    #[discriminant(2)]
+    Approved {},
+    #[discriminant(3)]
+    Rejected {},
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct Predicate {
+    name: String, // Property name the result is published under, e.g. "over_18"
+    property_name: String, // Secret property the predicate is evaluated on
+    kind: PredicateKind,
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub enum PredicateKind {
+    // The property holds a date of birth encoded as YYYYMMDD
+    #[discriminant(0)]
+    MinimumAge { years: u32 },
+    // The property holds a numeric code, e.g. an ISO 3166-1 numeric country code
+    #[discriminant(1)]
+    InSet { codes: Vec<u16> },
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct PredicateResult {
+    predicate_id: u32,
+    name: String,
+    result: bool,
+    evaluated_at: i64,
+    attestation_id: AttestationId, // Signature of the ZK nodes over the result
+}
+
+#[derive(ReadWriteRPC, CreateTypeSpec, ReadWriteState, Clone)]
+pub struct PendingEvaluation {
+    kyc_idx: u128,
+    predicate_id: u32,
+    output_variables: Vec<SecretVarId>, // Known once the computation completed, cleared once opened
+    result: Option<bool>, // Known once the output has been opened, the attestation is requested for it
+}
+
+impl PendingEvaluation {
+    // What the ZK nodes are asked to sign, (KYC ID, Predicate ID, result), so it can be checked off-chain
+    fn attestation_data(&self) -> Option<Vec<u8>> {
+        let result = self.result?;
+        let mut data: Vec<u8> = self.kyc_idx.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.predicate_id.to_be_bytes());
+        data.push(result as u8);
+        Some(data)
+    }
+}
+
+const MAX_SET_CODE: u16 = (zk_compute::SET_MASK_WORDS * 128) as u16;
+
+// Converts a unix timestamp to a YYYYMMDD number in UTC, see http://howardhinnant.github.io/date_algorithms.html
+fn date_number_from_unix_millis(unix_millis: i64) -> i128 {
+    let days = unix_millis.div_euclid(86_400_000) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year * 10_000 + month * 100 + day) as i128
+}
+
+impl ContractState {
+    fn is_reviewer(&self, account: &Address) -> bool {
+        self.reviewers.contains(account)
+    }
+
+    // Only one KYC per Applicant DID may be pending or approved at any time
+    fn has_active_kyc(&self, applicant_did: &str) -> bool {
+        self.kycs.values().any(|kyc| {
+            kyc.applicant_did == applicant_did
+                && matches!(kyc.status, KycStatus::Submitted {} | KycStatus::UnderReview {} | KycStatus::Approved {})
+        })
+    }
+
+    // Keeps the failure in state and returns it to the caller, instead of reverting the callback
+    fn record_failure(&mut self, failure: FailedSubmission) -> Vec<EventGroup> {
+        let mut event_group_builder = EventGroup::builder();
+        event_group_builder.return_data(failure.clone());
+
+        if self.failed_submissions.len() >= MAX_FAILED_SUBMISSIONS {
+            self.failed_submissions.remove(0);
+        }
+        self.failed_submissions.push(failure);
+
+        vec![event_group_builder.build()]
+    }
+}
+
+
+#[action(shortname = 0x01, zk = true)]
+pub fn configure_registry_address(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    target_registry_address: Address,
+    target_storage_address: Address,
+) -> ContractState {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+
+    state.registry_address = target_registry_address;
+    state.storage_adddress = target_storage_address;
+
+    state
+}
+
+#[action(shortname = 0x06, zk = true)]
+pub fn add_reviewer(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    reviewer: Address,
+) -> ContractState {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+
+    if !state.reviewers.contains(&reviewer) {
+        state.reviewers.push(reviewer);
+    }
+
+    state
+}
+
+#[action(shortname = 0x07, zk = true)]
+pub fn remove_reviewer(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    reviewer: Address,
+) -> ContractState {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+    // Only the assigned Reviewer can decide, their open reviews have to be reassigned first
+    assert!(
+        !state.kycs.values().any(|kyc| kyc.status == (KycStatus::UnderReview {}) && kyc.reviewer == Some(reviewer)),
+        "Reviewer Still Holds Open Reviews!"
+    );
+
+    state.reviewers.retain(|existing| *existing != reviewer);
+
+    state
+}
+
+#[action(shortname = 0x02, zk = true)]
+pub fn upload_kyc(
+    context: ContractContext,
+    state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    applicant_did: String,
+    public_info: Vec<SubjectInfo>,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(state.registry_address.identifier != [0x00; 20], "Please configure a valid DID Registry Address!");
+    assert!(!state.has_active_kyc(&applicant_did), "Active KYC Already Exists for DID!");
+
+    let mut event_group_builder = EventGroup::builder();
+    let copied_did = applicant_did.clone();
+
+    let new_kyc : Kyc = Kyc {
+        applicant_did,
+        submitter: context.sender,
+        public_info,
+        secret_properties: vec![],
+        reviewer: None,
+        status: KycStatus::Submitted {},
+        predicate_results: vec![],
+        vc_id: None, };
+    // Call the DID Registry Contract to check if the Sender has the right to upload KYC for a certain DID
+    // 0x05 is the Shortname for the method implemented on the Registry Contract, needs to be consistent
+    event_group_builder
+        .call(state.registry_address, Shortname::from_u32(0x05))
+        .argument(copied_did)
+        .argument(context.sender)
+        .done();
+
+    event_group_builder
+        .with_callback(SHORTNAME_UPLOAD_KYC_CALLBACK)
+        .argument(new_kyc)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[callback(shortname = 0x12, zk = true)]
+pub fn upload_kyc_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    new_kyc: Kyc,
+) -> (ContractState, Vec<EventGroup>) {
+    let failure_reason = if !callback_context.success {
+        Some("DID Not Registered or Not Authorized!")
+    } else if state.has_active_kyc(&new_kyc.applicant_did) {
+        // Re-check, another upload for the same DID may have landed while this one was in flight
+        Some("Active KYC Already Exists for DID!")
+    } else {
+        None
+    };
+    if let Some(reason) = failure_reason {
+        let events = state.record_failure(FailedSubmission {
+            kyc_id: None,
+            applicant_did: new_kyc.applicant_did,
+            sender: new_kyc.submitter,
+            reason: reason.to_string(),
+            failed_at: context.block_production_time,
+        });
+        return (state, events);
+    }
+
+    let kyc_id: u128 = state.next_kyc_id;
+    state.next_kyc_id += 1;
+    state.kycs.insert(kyc_id, new_kyc);
+
+    // Return the assigned KYC ID so the submitter can attach the secret properties
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(kyc_id);
+
+    (state, vec![event_group_builder.build()])
+}
+
+// Sensitive values are encoded off-chain into 128 bits, e.g. a passport number as up to 16
+// ASCII bytes or a date as the number YYYYMMDD, which MinimumAge predicates compare against
+#[zk_on_secret_input(shortname = 0x40, secret_type = "Sbi128")]
+pub fn upload_secret_property(
+    context: ContractContext,
+    state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    kyc_idx: u128,
+    property_name: String,
+) -> (ContractState, Vec<EventGroup>, ZkInputDef<SecretVarMetadata, Sbi128>) {
+
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    let kyc = state.kycs.get(&kyc_idx).unwrap();
+    assert!(kyc.submitter == context.sender, "Not Authorized!");
+    assert!(kyc.status == (KycStatus::Submitted {}), "KYC Already Under Review!");
+
+    let input_def = ZkInputDef::with_metadata(
+        Some(SHORTNAME_SECRET_PROPERTY_INPUTTED),
+        SecretVarMetadata { kyc_idx, property_name },
+    );
+
+    (state, vec![], input_def)
+}
+
+#[zk_on_variable_inputted(shortname = 0x41)]
+pub fn secret_property_inputted(
+    _context: ContractContext,
+    mut state: ContractState,
+    zk_state: ZkState<SecretVarMetadata>,
+    variable_id: SecretVarId,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    let metadata = zk_state.get_variable(variable_id).unwrap().metadata.clone();
+    let kyc = state.kycs.get_mut(&metadata.kyc_idx).unwrap();
+
+    // The review may have started while the input was being secret-shared
+    if kyc.status != (KycStatus::Submitted {}) {
+        return (state, vec![], vec![ZkStateChange::DeleteVariables { variables_to_delete: vec![variable_id] }]);
+    }
+
+    kyc.secret_properties.push(SecretProperty {
+        property_name: metadata.property_name,
+        variable_id,
+    });
+
+    (state, vec![], vec![])
+}
+
+// Transfers the secret variables to the Reviewer, only they can fetch and reconstruct the shares
+#[action(shortname = 0x05, zk = true)]
+pub fn start_review(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    kyc_idx: u128,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    assert!(state.is_reviewer(&context.sender), "Not Authorized!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    assert!(kyc.status == (KycStatus::Submitted {}), "KYC Already Under Review!");
+    assert!(kyc.submitter != context.sender, "Submitter Cannot Review Own KYC!");
+
+    kyc.status = KycStatus::UnderReview {};
+    kyc.reviewer = Some(context.sender);
+
+    let transfers = kyc
+        .secret_properties
+        .iter()
+        .map(|property| ZkStateChange::TransferVariable {
+            variable: property.variable_id,
+            new_owner: context.sender,
+        })
+        .collect();
+
+    (state, vec![], transfers)
+}
+
+// Hands an open review to another Reviewer, e.g. when the assigned one leaves. The previous
+// Reviewer may already have reconstructed the values, reassigning cannot take them back
+#[action(shortname = 0x0B, zk = true)]
+pub fn reassign_review(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    kyc_idx: u128,
+    new_reviewer: Address,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+    assert!(state.is_reviewer(&new_reviewer), "Not a Reviewer!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    assert!(kyc.status == (KycStatus::UnderReview {}), "KYC Not Under Review!");
+    assert!(kyc.submitter != new_reviewer, "Submitter Cannot Review Own KYC!");
+    assert!(kyc.reviewer != Some(new_reviewer), "Reviewer Already Assigned!");
+
+    kyc.reviewer = Some(new_reviewer);
+
+    let transfers = kyc
+        .secret_properties
+        .iter()
+        .map(|property| ZkStateChange::TransferVariable {
+            variable: property.variable_id,
+            new_owner: new_reviewer,
+        })
+        .collect();
+
+    (state, vec![], transfers)
+}
+
+#[action(shortname = 0x03, zk = true)]
+pub fn approve_kyc(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    kyc_idx: u128,
+    decision: bool,
+) -> ContractState {
+
+    assert!(state.is_reviewer(&context.sender), "Not Authorized!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    assert!(kyc.status == (KycStatus::UnderReview {}), "KYC Not Under Review!");
+    // Only the Reviewer who received the secret values can decide, and only while still a Reviewer
+    assert!(kyc.reviewer == Some(context.sender), "Not Authorized!");
+
+    if decision {
+        kyc.status = KycStatus::Approved {};
+    } else {
+        kyc.status = KycStatus::Rejected {};
+    }
+
+    state
+}
+
+#[action(shortname = 0x08, zk = true)]
+pub fn define_predicate(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    name: String,
+    property_name: String,
+    kind: PredicateKind,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+    if let PredicateKind::InSet { codes } = &kind {
+        assert!(!codes.is_empty(), "Predicate Set Is Empty!");
+        assert!(codes.iter().all(|code| *code < MAX_SET_CODE), "Predicate Set Code Out of Range!");
+    }
+
+    let predicate_id = state.next_predicate_id;
+    state.next_predicate_id += 1;
+    state.predicates.insert(predicate_id, Predicate { name, property_name, kind });
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(predicate_id);
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[action(shortname = 0x09, zk = true)]
+pub fn evaluate_predicate(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    kyc_idx: u128,
+    predicate_id: u32,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    assert!(state.is_reviewer(&context.sender), "Not Authorized!");
+    assert!(state.pending_evaluation.is_none(), "Predicate Evaluation Already Running!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    assert!(state.predicates.contains_key(&predicate_id), "Predicate Not Found!");
+
+    let kyc = state.kycs.get(&kyc_idx).unwrap();
+    // Only evaluate on values a Reviewer has checked
+    assert!(kyc.status == (KycStatus::Approved {}), "KYC Not Approved!");
+
+    let predicate = state.predicates.get(&predicate_id).unwrap();
+    let variable_id = kyc
+        .secret_properties
+        .iter()
+        .find(|property| property.property_name == predicate.property_name)
+        .expect("Secret Property Not Found!")
+        .variable_id;
+
+    let output_metadata = SecretVarMetadata { kyc_idx, property_name: predicate.name.clone() };
+    let computation = match &predicate.kind {
+        PredicateKind::MinimumAge { years } => {
+            // Born on or before the same day `years` years ago
+            let cutoff = date_number_from_unix_millis(context.block_production_time) - (*years as i128) * 10_000;
+            zk_compute::at_most_start(variable_id, cutoff, Some(SHORTNAME_PREDICATE_COMPUTED), &output_metadata)
+        }
+        PredicateKind::InSet { codes } => {
+            let mut masks = [0u128; zk_compute::SET_MASK_WORDS];
+            for code in codes {
+                masks[*code as usize / 128] |= 1u128 << (*code as usize % 128);
+            }
+            zk_compute::in_set_start(
+                variable_id,
+                masks[0], masks[1], masks[2], masks[3], masks[4], masks[5], masks[6], masks[7],
+                Some(SHORTNAME_PREDICATE_COMPUTED),
+                &output_metadata,
+            )
+        }
+    };
+
+    state.pending_evaluation = Some(PendingEvaluation { kyc_idx, predicate_id, output_variables: vec![], result: None });
+
+    (state, vec![], vec![computation])
+}
+
+#[zk_on_compute_complete(shortname = 0x42)]
+pub fn predicate_computed(
+    _context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    output_variables: Vec<SecretVarId>,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    // Output of an evaluation that was cancelled while computing is never opened
+    match state.pending_evaluation.as_mut() {
+        Some(evaluation) if evaluation.output_variables.is_empty() && evaluation.result.is_none() => {
+            evaluation.output_variables = output_variables.clone();
+        }
+        _ => {
+            return (state, vec![], vec![ZkStateChange::DeleteVariables { variables_to_delete: output_variables }]);
+        }
+    }
+
+    // The single output bit is the only thing ever opened
+    (state, vec![], vec![ZkStateChange::OpenVariables { variables: output_variables }])
+}
+
+#[zk_on_variables_opened]
+pub fn predicate_opened(
+    _context: ContractContext,
+    mut state: ContractState,
+    zk_state: ZkState<SecretVarMetadata>,
+    opened_variables: Vec<SecretVarId>,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    // Output of a cancelled evaluation is discarded, it must not be recorded on the pending one
+    let evaluation = match state.pending_evaluation.as_mut() {
+        Some(evaluation) if !evaluation.output_variables.is_empty() && evaluation.output_variables == opened_variables => {
+            evaluation
+        }
+        _ => {
+            return (state, vec![], vec![ZkStateChange::DeleteVariables { variables_to_delete: opened_variables }]);
+        }
+    };
+    let output_id = opened_variables[0];
+    let data = zk_state.get_variable(output_id).unwrap().data.clone().unwrap();
+
+    // The opened output is deleted below, a cancellation must not delete it again
+    evaluation.output_variables = vec![];
+    evaluation.result = Some(data[0] & 0x01 == 0x01);
+    let data_to_attest = evaluation.attestation_data().unwrap();
+
+    (
+        state,
+        vec![],
+        vec![
+            ZkStateChange::DeleteVariables { variables_to_delete: opened_variables },
+            ZkStateChange::Attest { data_to_attest },
+        ],
+    )
+}
+
+#[zk_on_attestation_complete]
+pub fn predicate_attested(
+    context: ContractContext,
+    mut state: ContractState,
+    zk_state: ZkState<SecretVarMetadata>,
+    attestation_id: AttestationId,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    // An attestation requested by a cancelled evaluation signs other data and is ignored. One that
+    // signs the exact same statement is a valid signature over the pending result and is accepted
+    let signed_data = zk_state
+        .data_attestations
+        .iter()
+        .find(|attestation| attestation.attestation_id == attestation_id)
+        .map(|attestation| attestation.data.clone());
+    let matches_pending = match &state.pending_evaluation {
+        Some(evaluation) => signed_data.is_some() && evaluation.attestation_data() == signed_data,
+        None => false,
+    };
+    if !matches_pending {
+        return (state, vec![], vec![]);
+    }
+
+    let evaluation = state.pending_evaluation.take().unwrap();
+    let name = state.predicates.get(&evaluation.predicate_id).unwrap().name.clone();
+    let kyc = state.kycs.get_mut(&evaluation.kyc_idx).unwrap();
+
+    // The attested result replaces every earlier result of the same predicate
+    kyc.predicate_results.retain(|outcome| outcome.predicate_id != evaluation.predicate_id);
+    kyc.predicate_results.push(PredicateResult {
+        predicate_id: evaluation.predicate_id,
+        name,
+        result: evaluation.result.unwrap(),
+        evaluated_at: context.block_production_time,
+        attestation_id,
+    });
+
+    (state, vec![], vec![])
+}
+
+// Clears an evaluation that never completed, e.g. because the attestation was never signed, so the
+// next one can start. Output variables of the computation that were not opened yet are deleted
+#[action(shortname = 0x0A, zk = true)]
+pub fn cancel_predicate_evaluation(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+) -> (ContractState, Vec<EventGroup>, Vec<ZkStateChange>) {
+
+    assert!(context.sender == state.owner || state.is_reviewer(&context.sender), "Not Authorized!");
+    let evaluation = state.pending_evaluation.take().expect("No Predicate Evaluation Running!");
+
+    let mut changes = vec![];
+    if !evaluation.output_variables.is_empty() {
+        changes.push(ZkStateChange::DeleteVariables { variables_to_delete: evaluation.output_variables });
+    }
+
+    (state, vec![], changes)
+}
+
+#[action(shortname = 0x04, zk = true)]
+pub fn create_vc(
+    context: ContractContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    kyc_idx: u128,
+    issuer_did: String,
+    valid_since: i64, // Unix milliseconds
+    valid_until: i64, // Unix milliseconds
+    description: String,
+) -> (ContractState, Vec<EventGroup>) {
+
+    assert!(context.sender == state.owner, "Not Authorized!");
+    assert!(state.storage_adddress.identifier != [0x00; 20], "Please configure a valid VC Storage Address!");
+    assert!(state.kycs.contains_key(&kyc_idx), "KYC Not Found!");
+    assert!(valid_since < valid_until, "Invalid Validity Window!");
+    assert!(valid_until > context.block_production_time, "VC Validity Already Ended!");
+
+    let vc_id: u128 = state.next_vc_id;
+    state.next_vc_id += 1;
+
+    let storage_address = state.storage_adddress;
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    assert!(kyc.status == (KycStatus::Approved {}), "KYC Not Approved!");
+    assert!(kyc.vc_id.is_none(), "VC Already Issued for KYC!");
+    kyc.vc_id = Some(vc_id);
+
+    // Secret properties are referenced by their variable ID, their values never leave the MPC nodes
+    let mut subject_info = kyc.public_info.clone();
+    subject_info.extend(kyc.secret_properties.iter().map(|property| SubjectInfo {
+        property_name: property.property_name.clone(),
+        property_value: format!("zk:{}", property.variable_id.raw_id),
+    }));
+    // Attested predicate outcomes are embedded as plain booleans
+    subject_info.extend(kyc.predicate_results.iter().map(|outcome| SubjectInfo {
+        property_name: outcome.name.clone(),
+        property_value: outcome.result.to_string(),
+    }));
+
+    let mut event_group_builder = EventGroup::builder();
+
+    // Same upload_vc entrypoint on the VC Storage Contract as pbc-kyc, no Merkle root is kept here
+    event_group_builder
+        .call(storage_address, Shortname::from_u32(0x02))
+        .argument(issuer_did)
+        .argument(vc_id)
+        .argument(kyc.applicant_did.clone())
+        .argument(subject_info)
+        .argument(valid_since)
+        .argument(valid_until)
+        .argument(description)
+        .argument(false)
+        .argument([0x00u8; 32])
+        .done();
+
+    event_group_builder
+        .with_callback(SHORTNAME_CREATE_VC_CALLBACK)
+        .argument(kyc_idx)
+        .argument(context.sender)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[callback(shortname = 0x14, zk = true)]
+pub fn create_vc_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: ContractState,
+    _zk_state: ZkState<SecretVarMetadata>,
+    kyc_idx: u128,
+    sender: Address,
+) -> (ContractState, Vec<EventGroup>) {
+    if callback_context.success {
+        return (state, vec![]);
+    }
+
+    // Release the KYC so the VC can be issued again
+    let kyc = state.kycs.get_mut(&kyc_idx).unwrap();
+    kyc.vc_id = None;
+    let applicant_did = kyc.applicant_did.clone();
+    let events = state.record_failure(FailedSubmission {
+        kyc_id: Some(kyc_idx),
+        applicant_did,
+        sender,
+        reason: "VC Failed to Upload!".to_string(),
+        failed_at: context.block_production_time,
+    });
+
+    (state, events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch_is_first_of_january_1970() {
+        assert_eq!(date_number_from_unix_millis(0), 19700101);
+        assert_eq!(date_number_from_unix_millis(86_399_999), 19700101);
+        assert_eq!(date_number_from_unix_millis(86_400_000), 19700102);
+    }
+
+    #[test]
+    fn leap_day_is_kept() {
+        assert_eq!(date_number_from_unix_millis(951_696_000_000), 20000228);
+        assert_eq!(date_number_from_unix_millis(951_782_400_000), 20000229);
+        assert_eq!(date_number_from_unix_millis(951_868_800_000), 20000301);
+    }
+
+    #[test]
+    fn year_boundary_rolls_over() {
+        assert_eq!(date_number_from_unix_millis(1_704_067_199_999), 20231231);
+        assert_eq!(date_number_from_unix_millis(1_704_067_200_000), 20240101);
+    }
+
+    #[test]
+    fn pre_1970_timestamps_count_backwards() {
+        assert_eq!(date_number_from_unix_millis(-1), 19691231);
+        assert_eq!(date_number_from_unix_millis(-86_400_000), 19691231);
+        assert_eq!(date_number_from_unix_millis(-86_400_001), 19691230);
+        assert_eq!(date_number_from_unix_millis(-315_619_200_000), 19600101);
+    }
+}
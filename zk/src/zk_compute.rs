@@ -0,0 +1,39 @@
+//! ZK computations for predicates over secret properties, each returns a single secret bit
+//! which is opened afterwards, the property value itself is never revealed
+
+use pbc_zk::*;
+
+// Codes of set predicates are limited to [0, 1024), passed as 8 bitmask words
+pub const SET_MASK_WORDS: usize = 8;
+
+#[zk_compute(shortname = 0x61)]
+pub fn at_most(variable_id: SecretVarId, threshold: i128) -> Sbi1 {
+    let value = load_sbi::<Sbi128>(variable_id);
+    value <= Sbi128::from(threshold)
+}
+
+#[zk_compute(shortname = 0x62)]
+pub fn in_set(
+    variable_id: SecretVarId,
+    mask_0: u128,
+    mask_1: u128,
+    mask_2: u128,
+    mask_3: u128,
+    mask_4: u128,
+    mask_5: u128,
+    mask_6: u128,
+    mask_7: u128,
+) -> Sbi1 {
+    let value = load_sbi::<Sbi128>(variable_id);
+    let masks: [u128; SET_MASK_WORDS] = [mask_0, mask_1, mask_2, mask_3, mask_4, mask_5, mask_6, mask_7];
+    let mut result = Sbi1::from(false);
+    // The masks are public, so only the codes in the set are compared
+    for word in 0..SET_MASK_WORDS {
+        for bit in 0..128 {
+            if (masks[word] >> bit) & 1 == 1 {
+                result = result | (value == Sbi128::from((word * 128 + bit) as i128));
+            }
+        }
+    }
+    result
+}